@@ -0,0 +1,101 @@
+//! A [Count-Min Sketch](https://en.wikipedia.org/wiki/Count%E2%80%93min_sketch), used by
+//! [`LFUCache::with_capacity_tinylfu`](crate::LFUCache::with_capacity_tinylfu) to estimate how often a key has been
+//! seen without storing the key itself.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Number of independent hash rows. Four is the standard choice for Count-Min Sketches used as frequency filters.
+const DEPTH: usize = 4;
+
+/// A fixed-size, approximate frequency counter for a stream of keys.
+///
+/// Every `increment` bumps `DEPTH` counters per key; `estimate` returns the minimum of those counters, which never
+/// under-counts and only over-counts on hash collisions. Counters are periodically halved ("aged") so the estimate
+/// tracks recent activity rather than all-time totals.
+#[derive(Clone, Debug)]
+pub struct CountMinSketch {
+    width: usize,
+    counters: Vec<u8>,
+    sample_size: u64,
+    max_sample_size: u64,
+}
+
+impl CountMinSketch {
+    /// Creates a sketch sized for roughly `capacity` distinct hot keys.
+    pub fn new(capacity: usize) -> CountMinSketch {
+        let width = capacity.next_power_of_two().max(16);
+        CountMinSketch {
+            width,
+            counters: vec![0u8; DEPTH * width],
+            sample_size: 0,
+            max_sample_size: (capacity as u64).saturating_mul(10).max(1),
+        }
+    }
+
+    fn row_hash<K: Hash>(key: &K, row: usize) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn index(&self, row: usize, hash: u64) -> usize {
+        row * self.width + (hash as usize & (self.width - 1))
+    }
+
+    /// Records one occurrence of `key`, aging the whole sketch once enough samples have accumulated.
+    pub fn increment<K: Hash>(&mut self, key: &K) {
+        for row in 0..DEPTH {
+            let idx = self.index(row, Self::row_hash(key, row));
+            self.counters[idx] = self.counters[idx].saturating_add(1);
+        }
+        self.sample_size += 1;
+        if self.sample_size >= self.max_sample_size {
+            self.age();
+        }
+    }
+
+    /// Returns the estimated number of times `key` has been seen (recently).
+    pub fn estimate<K: Hash>(&self, key: &K) -> u8 {
+        (0..DEPTH)
+            .map(|row| self.counters[self.index(row, Self::row_hash(key, row))])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Halves every counter and resets the sample count, so stale frequencies decay over time.
+    fn age(&mut self) {
+        for counter in self.counters.iter_mut() {
+            *counter /= 2;
+        }
+        self.sample_size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_reflects_increments() {
+        let mut sketch = CountMinSketch::new(16);
+        for _ in 0..5 {
+            sketch.increment(&"hot");
+        }
+        sketch.increment(&"cold");
+        assert!(sketch.estimate(&"hot") >= sketch.estimate(&"cold"));
+        assert!(sketch.estimate(&"hot") >= 5);
+    }
+
+    #[test]
+    fn aging_decays_counters() {
+        let mut sketch = CountMinSketch::new(4);
+        let rounds = sketch.max_sample_size * 3;
+        for _ in 0..rounds {
+            sketch.increment(&"key");
+        }
+        // Without aging the estimate would track `rounds`; aging keeps it bounded well below that.
+        assert!((sketch.estimate(&"key") as u64) < rounds);
+    }
+}
@@ -3,6 +3,11 @@
 //! It supports insertions and retrievals, both of which are performed in constant time. In the event of tie between
 //! two least frequently used entries, the least *recently* used entry is evicted.
 //!
+//! Entries may carry a variable [`Entry::weight`] (defaulting to `1`), in which case `capacity` is a weight budget
+//! rather than a simple entry count, and eviction can be customized with a [`Policy`]. [`LFUCache::with_capacity_s3fifo`]
+//! opts into the cheaper [S3-FIFO](https://blog.jasony.me/system/cache/2023/08/01/s3fifo) algorithm instead.
+//! [`LFUCache::with_capacity_and_hasher`] swaps out the default [`RandomState`](std::collections::hash_map::RandomState)
+//! hasher for a custom one. [`ShardedLFUCache`] partitions an [`LFUCache`] across several locks for concurrent use.
 //!
 //!
 //! # Examples
@@ -24,26 +29,122 @@
 //! # }
 //! ```
 
+mod count_min_sketch;
+mod s3fifo;
+mod sharded;
+
+pub use count_min_sketch::CountMinSketch;
+pub use sharded::ShardedLFUCache;
+
 use linked_hash_set::LinkedHashSet;
-use std::collections::hash_map::{IntoIter, Iter};
+use s3fifo::S3Fifo;
+use std::collections::hash_map::{IntoIter, Iter, RandomState};
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
 use std::ops::Index;
 use std::rc::Rc;
 
+/// A value that can be stored in an [`LFUCache`] with a weight other than `1`.
+///
+/// The default weight of `1` reproduces plain entry-counting behavior, so implementing this trait is optional for
+/// most `V`; a handful of common primitive types implement it below so the existing count-based API keeps working
+/// unchanged.
+pub trait Entry {
+    /// The "size" this entry counts against the cache's `capacity`. Defaults to `1`.
+    fn weight(&self) -> u64 {
+        1
+    }
+}
+
+macro_rules! impl_entry_with_default_weight {
+    ($($t:ty),*) => {
+        $(impl Entry for $t {})*
+    };
+}
+
+impl_entry_with_default_weight!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, bool, char, f32, f64, String
+);
+
+impl Entry for &str {}
+
+/// Lets a caller veto or react to eviction, e.g. to pin in-use entries or flush evicted values to a backing store.
+///
+/// Both methods default to the original unconditional-eviction behavior, so implementing either is optional.
+pub trait Policy<K, V> {
+    /// Returns `false` to veto evicting this entry; defaults to always allowing eviction.
+    fn can_evict(&self, _key: &K, _value: &V) -> bool {
+        true
+    }
+
+    /// Called with an entry right after it has been evicted; defaults to discarding it.
+    fn evict(&mut self, _key: K, _value: V) {}
+}
+
+/// The default [`Policy`]: every entry is evictable and evicted values are simply dropped.
+#[derive(Clone, Debug, Default)]
+pub struct NoopPolicy;
+
+impl<K, V> Policy<K, V> for NoopPolicy {}
+
 #[derive(Clone, Debug)]
-pub struct LFUCache<K: Hash + Eq + Clone, V> {
-    values: HashMap<K, ValueCounter<V>>,
-    frequency_bin: HashMap<usize, LinkedHashSet<K>>,
+pub struct LFUCache<K: Hash + Eq + Clone, V: Entry, P: Policy<K, V> = NoopPolicy, S: BuildHasher = RandomState> {
+    values: HashMap<K, ValueCounter<V>, S>,
     capacity: usize,
-    min_frequency: usize,
+    total_weight: u64,
+    eviction: Eviction<K, V, P, S>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A snapshot of an [`LFUCache`]'s cumulative [`get`](LFUCache::get)/[`get_mut`](LFUCache::get_mut) hit and miss
+/// counts, as returned by [`LFUCache::stats`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// The fraction of accesses that were hits, in `[0.0, 1.0]`. Returns `0.0` if there have been no accesses yet.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// The bookkeeping an [`LFUCache`] uses to choose an eviction victim, selected at construction time.
+#[derive(Clone, Debug)]
+enum Eviction<K: Hash + Eq + Clone, V: Entry, P: Policy<K, V>, S: BuildHasher> {
+    /// The default mode: keys are grouped into per-frequency bins, and the least-frequently (then least-recently)
+    /// used key is evicted, subject to `policy`. Optionally guarded by a W-TinyLFU `admission` filter.
+    FrequencyBins {
+        frequency_bin: HashMap<usize, LinkedHashSet<K, S>, S>,
+        min_frequency: usize,
+        policy: P,
+        admission: Option<CountMinSketch>,
+        // `V` only appears in the `Policy<K, V>` bound above, which isn't enough to make it a
+        // load-bearing type parameter; this marker keeps it so.
+        _value: PhantomData<fn() -> V>,
+    },
+    /// The [S3-FIFO](https://blog.jasony.me/system/cache/2023/08/01/s3fifo) mode: see [`S3Fifo`].
+    S3Fifo(S3Fifo<K, S>),
 }
 
 #[derive(Clone, Debug)]
 struct ValueCounter<V> {
     value: V,
     count: usize,
+    weight: u64,
+    s3fifo_counter: u8,
 }
 
 impl<V> ValueCounter<V> {
@@ -52,19 +153,91 @@ impl<V> ValueCounter<V> {
     }
 }
 
-impl<K: Hash + Eq + Clone, V> LFUCache<K, V> {
-    pub fn with_capacity(capacity: usize) -> LFUCache<K, V> {
+impl<K: Hash + Eq + Clone, V: Entry> LFUCache<K, V, NoopPolicy, RandomState> {
+    pub fn with_capacity(capacity: usize) -> LFUCache<K, V, NoopPolicy, RandomState> {
+        Self::with_capacity_and_hasher(capacity, RandomState::default())
+    }
+
+    /// Creates a cache guarded by a [W-TinyLFU](https://arxiv.org/abs/1512.00727) admission filter: once the cache
+    /// is full, a brand-new key is only admitted if a [`CountMinSketch`] estimates it is accessed at least as often
+    /// as the current eviction victim, protecting hot entries from one-off scan traffic.
+    pub fn with_capacity_tinylfu(capacity: usize) -> LFUCache<K, V, NoopPolicy, RandomState> {
+        let mut cache = Self::with_capacity(capacity);
+        if let Eviction::FrequencyBins { admission, .. } = &mut cache.eviction {
+            *admission = Some(CountMinSketch::new(capacity));
+        }
+        cache
+    }
+
+    /// Creates a cache that uses [S3-FIFO](https://blog.jasony.me/system/cache/2023/08/01/s3fifo) instead of
+    /// frequency bins to pick an eviction victim: cheaper bookkeeping for comparable hit ratios, at the cost of the
+    /// weighted-capacity, [`Policy`] and admission-filter features the frequency-bin mode supports.
+    pub fn with_capacity_s3fifo(capacity: usize) -> LFUCache<K, V, NoopPolicy, RandomState> {
         if capacity == 0 {
             panic!("Unable to create cache: capacity is {:?}", capacity);
         }
         LFUCache {
-            values: HashMap::new(),
-            frequency_bin: HashMap::new(),
+            values: HashMap::default(),
             capacity,
-            min_frequency: 0,
+            total_weight: 0,
+            eviction: Eviction::S3Fifo(S3Fifo::new(capacity, RandomState::default())),
+            hits: 0,
+            misses: 0,
         }
     }
+}
 
+impl<K: Hash + Eq + Clone, V: Entry, S: BuildHasher + Default + Clone> LFUCache<K, V, NoopPolicy, S> {
+    /// Like [`with_capacity`](LFUCache::with_capacity), but builds the value map and the frequency-bin map with
+    /// `hash_builder` instead of [`RandomState`], e.g. to plug in a faster or DoS-resistant hasher (such as ahash or
+    /// fxhash) on a hot-path cache. The per-frequency key sets inside the frequency-bin map are built with
+    /// `S::default()`, not with `hash_builder`.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> LFUCache<K, V, NoopPolicy, S> {
+        if capacity == 0 {
+            panic!("Unable to create cache: capacity is {:?}", capacity);
+        }
+        LFUCache {
+            values: HashMap::with_hasher(hash_builder.clone()),
+            capacity,
+            total_weight: 0,
+            eviction: Eviction::FrequencyBins {
+                frequency_bin: HashMap::with_hasher(hash_builder),
+                min_frequency: 0,
+                policy: NoopPolicy,
+                admission: None,
+                _value: PhantomData,
+            },
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Entry, P: Policy<K, V>> LFUCache<K, V, P, RandomState> {
+    /// Creates a cache with the given weight `capacity`, evicting through the given `policy` instead of
+    /// unconditionally evicting the least-frequently-used entry.
+    pub fn with_capacity_and_policy(capacity: usize, policy: P) -> LFUCache<K, V, P, RandomState> {
+        if capacity == 0 {
+            panic!("Unable to create cache: capacity is {:?}", capacity);
+        }
+        LFUCache {
+            values: HashMap::default(),
+            capacity,
+            total_weight: 0,
+            eviction: Eviction::FrequencyBins {
+                frequency_bin: HashMap::default(),
+                min_frequency: 0,
+                policy,
+                admission: None,
+                _value: PhantomData,
+            },
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Entry, P: Policy<K, V>, S: BuildHasher + Default> LFUCache<K, V, P, S> {
     pub fn contains(&self, key: &K) -> bool {
         self.values.contains_key(key)
     }
@@ -77,53 +250,295 @@ impl<K: Hash + Eq + Clone, V> LFUCache<K, V> {
         self.values.is_empty()
     }
 
+    /// The sum of [`Entry::weight`] across all entries currently in the cache.
+    pub fn total_weight(&self) -> u64 {
+        self.total_weight
+    }
+
+    /// The cache's current weight capacity, as last set by [`with_capacity`](LFUCache::with_capacity) or
+    /// [`set_capacity`](LFUCache::set_capacity).
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the cache's capacity at runtime. If `capacity` is smaller than the cache's current size, entries are
+    /// evicted until the cache fits, so long-lived caches can adapt to memory pressure without being rebuilt. If the
+    /// [`Policy`] pins every remaining entry, eviction stops and the cache stays over the new capacity.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        if capacity == 0 {
+            panic!("Unable to resize cache: capacity is {:?}", capacity);
+        }
+        self.capacity = capacity;
+        if let Eviction::S3Fifo(queues) = &mut self.eviction {
+            let small_capacity = (capacity / 10).max(1).min(capacity);
+            queues.small_capacity = small_capacity;
+            queues.main_capacity = capacity - small_capacity;
+        }
+        loop {
+            let fits = match &self.eviction {
+                Eviction::FrequencyBins { .. } => self.total_weight <= self.capacity as u64,
+                Eviction::S3Fifo(_) => self.values.len() <= self.capacity,
+            };
+            if self.values.is_empty() || fits {
+                break;
+            }
+            let len_before = self.values.len();
+            self.evict();
+            if self.values.len() == len_before {
+                // Every resident entry is pinned by the policy, so shrinking further isn't possible:
+                // stop rather than spin forever, leaving the cache over the new capacity.
+                break;
+            }
+        }
+    }
+
     pub fn remove(&mut self, key: K) -> Option<V> {
-        if let Some(value_counter) = self.values.get(&key) {
-            let count = value_counter.count;
-            self.frequency_bin.entry(count).or_default().remove(&key);
-            self.values.remove(&key).map(|x| x.value)
-        } else {
-            None
+        match &mut self.eviction {
+            Eviction::FrequencyBins { frequency_bin, .. } => {
+                if let Some(value_counter) = self.values.get(&key) {
+                    let count = value_counter.count;
+                    frequency_bin.entry(count).or_default().remove(&key);
+                } else {
+                    return None;
+                }
+            }
+            Eviction::S3Fifo(queues) => {
+                queues.small.remove(&key);
+                queues.main.remove(&key);
+            }
         }
+        self.values.remove(&key).map(|x| {
+            self.total_weight -= x.weight;
+            x.value
+        })
     }
 
     /// Returns the value associated with the given key (if it still exists)
     /// Method marked as mutable because it internally updates the frequency of the accessed key
     pub fn get(&mut self, key: &K) -> Option<&V> {
-        self.update_frequency_bin(key);
+        self.touch_on_access(key);
+        let found = self.values.contains_key(key);
+        self.record_access(found);
         self.values.get(&key).map(|x| &x.value)
     }
 
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        self.update_frequency_bin(key);
+        self.touch_on_access(key);
+        let found = self.values.contains_key(key);
+        self.record_access(found);
         self.values.get_mut(&key).map(|x| &mut x.value)
     }
 
-    fn update_frequency_bin(&mut self, key: &K) {
-        if let Some(value_counter) = self.values.get_mut(&key) {
-            let bin = self.frequency_bin.get_mut(&value_counter.count).unwrap();
-            bin.remove(&key);
-            let count = value_counter.count;
-            value_counter.inc();
-            if count == self.min_frequency && bin.is_empty() {
-                self.min_frequency += 1;
+    fn record_access(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+
+    /// A snapshot of cumulative hit/miss counts, for comparing eviction modes or tuning `capacity`.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            len: self.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Resets the cumulative hit/miss counters to zero, without affecting the cached entries themselves.
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    /// Records an access for eviction-ranking purposes: bumps the frequency bin (and admission-filter estimate) in
+    /// the default mode, or the saturating S3-FIFO access counter otherwise.
+    fn touch_on_access(&mut self, key: &K) {
+        match &mut self.eviction {
+            Eviction::FrequencyBins {
+                frequency_bin,
+                min_frequency,
+                admission,
+                ..
+            } => {
+                if let Some(sketch) = admission {
+                    sketch.increment(key);
+                }
+                if let Some(value_counter) = self.values.get_mut(key) {
+                    let bin = frequency_bin.get_mut(&value_counter.count).unwrap();
+                    bin.remove(key);
+                    let count = value_counter.count;
+                    value_counter.inc();
+                    if count == *min_frequency && bin.is_empty() {
+                        *min_frequency += 1;
+                    }
+                    frequency_bin.entry(count + 1).or_default().insert(key.clone());
+                }
+            }
+            Eviction::S3Fifo(_) => {
+                if let Some(value_counter) = self.values.get_mut(key) {
+                    value_counter.s3fifo_counter = (value_counter.s3fifo_counter + 1).min(3);
+                }
             }
-            self.frequency_bin
-                .entry(count + 1)
-                .or_default()
-                .insert(key.clone());
         }
     }
 
+    /// Evicts a single entry, chosen according to the cache's eviction mode. If every entry is pinned by a
+    /// [`Policy`], this is a no-op.
     fn evict(&mut self) {
-        let least_frequently_used_keys = self.frequency_bin.get_mut(&self.min_frequency).unwrap();
-        let least_recently_used = least_frequently_used_keys.pop_front().unwrap();
-        self.values.remove(&least_recently_used);
+        if matches!(self.eviction, Eviction::S3Fifo(_)) {
+            self.evict_s3fifo();
+        } else {
+            self.evict_frequency_bins();
+        }
+    }
+
+    fn evict_frequency_bins(&mut self) {
+        let (frequency_bin, min_frequency, policy) = match &mut self.eviction {
+            Eviction::FrequencyBins {
+                frequency_bin,
+                min_frequency,
+                policy,
+                ..
+            } => (frequency_bin, min_frequency, policy),
+            Eviction::S3Fifo(_) => return,
+        };
+
+        let mut frequencies: Vec<usize> = frequency_bin
+            .keys()
+            .copied()
+            .filter(|&f| f >= *min_frequency)
+            .collect();
+        frequencies.sort_unstable();
+
+        let mut victim = None;
+        'outer: for &freq in &frequencies {
+            if let Some(bin) = frequency_bin.get(&freq) {
+                for key in bin.iter() {
+                    let value = &self.values.get(key).unwrap().value;
+                    if policy.can_evict(key, value) {
+                        victim = Some((freq, key.clone()));
+                        break 'outer;
+                    }
+                }
+            }
+        }
+
+        let (freq, key) = match victim {
+            Some(v) => v,
+            None => return,
+        };
+
+        let bin = frequency_bin.get_mut(&freq).unwrap();
+        bin.remove(&key);
+        if freq == *min_frequency && bin.is_empty() {
+            *min_frequency = frequency_bin
+                .iter()
+                .filter(|(&f, b)| f > freq && !b.is_empty())
+                .map(|(&f, _)| f)
+                .min()
+                .unwrap_or(freq + 1);
+        }
+
+        let value_counter = self.values.remove(&key).unwrap();
+        self.total_weight -= value_counter.weight;
+        policy.evict(key, value_counter.value);
+    }
+
+    /// Repeatedly rebalances the S3-FIFO queues (promoting/re-queueing entries that were accessed since entering
+    /// their queue) until an entry with no remaining second chances is actually evicted.
+    fn evict_s3fifo(&mut self) {
+        loop {
+            let small_over = match &self.eviction {
+                Eviction::S3Fifo(q) => q.small.len() >= q.small_capacity,
+                Eviction::FrequencyBins { .. } => return,
+            };
+
+            if small_over {
+                let key = match &mut self.eviction {
+                    Eviction::S3Fifo(q) => q.small.pop_front().unwrap(),
+                    Eviction::FrequencyBins { .. } => unreachable!(),
+                };
+                let accessed = self
+                    .values
+                    .get(&key)
+                    .map(|vc| vc.s3fifo_counter > 0)
+                    .unwrap_or(false);
+                let in_ghost = match &self.eviction {
+                    Eviction::S3Fifo(q) => q.ghost.contains(&key),
+                    Eviction::FrequencyBins { .. } => false,
+                };
+                if accessed || in_ghost {
+                    if let Some(value_counter) = self.values.get_mut(&key) {
+                        value_counter.s3fifo_counter = 0;
+                    }
+                    if let Eviction::S3Fifo(q) = &mut self.eviction {
+                        q.ghost.remove(&key);
+                        q.main.insert(key);
+                    }
+                    continue;
+                }
+
+                if let Some(value_counter) = self.values.remove(&key) {
+                    self.total_weight -= value_counter.weight;
+                }
+                if let Eviction::S3Fifo(q) = &mut self.eviction {
+                    q.ghost.insert(key);
+                    while q.ghost.len() > self.capacity {
+                        q.ghost.pop_front();
+                    }
+                }
+                return;
+            }
+
+            let main_over = match &self.eviction {
+                Eviction::S3Fifo(q) => q.main.len() >= q.main_capacity,
+                Eviction::FrequencyBins { .. } => return,
+            };
+
+            if main_over {
+                let key = match &mut self.eviction {
+                    Eviction::S3Fifo(q) => q.main.pop_front().unwrap(),
+                    Eviction::FrequencyBins { .. } => unreachable!(),
+                };
+                let accessed = self
+                    .values
+                    .get(&key)
+                    .map(|vc| vc.s3fifo_counter > 0)
+                    .unwrap_or(false);
+                if accessed {
+                    if let Some(value_counter) = self.values.get_mut(&key) {
+                        value_counter.s3fifo_counter -= 1;
+                    }
+                    if let Eviction::S3Fifo(q) = &mut self.eviction {
+                        q.main.insert(key);
+                    }
+                    continue;
+                }
+
+                if let Some(value_counter) = self.values.remove(&key) {
+                    self.total_weight -= value_counter.weight;
+                }
+                return;
+            }
+
+            // Neither queue is over its quota; there's nothing to evict right now.
+            return;
+        }
     }
 
+    /// Returns the least-recently-used key of the lowest frequency bin, which eviction picks next unless a [`Policy`]
+    /// pins it. In S3-FIFO mode this is an approximation (the oldest entry in `S`, or in `M` when `S` is empty), since
+    /// there's no single "LFU key" concept.
     pub fn peek_lfu_key(&mut self) -> Option<K> {
-        let least_frequently_used_keys = self.frequency_bin.get_mut(&self.min_frequency).unwrap();
-        least_frequently_used_keys.front().map(|x| x.clone())
+        match &self.eviction {
+            Eviction::FrequencyBins { frequency_bin, min_frequency, .. } => {
+                frequency_bin.get(min_frequency).and_then(|bin| bin.front().cloned())
+            }
+            Eviction::S3Fifo(q) => q.small.front().or_else(|| q.main.front()).cloned(),
+        }
     }
 
     pub fn iter(&self) -> LfuIterator<K, V> {
@@ -133,21 +548,124 @@ impl<K: Hash + Eq + Clone, V> LFUCache<K, V> {
     }
 
     pub fn set(&mut self, key: K, value: V) {
+        if matches!(self.eviction, Eviction::S3Fifo(_)) {
+            self.set_s3fifo(key, value);
+        } else {
+            self.set_frequency_bins(key, value);
+        }
+    }
+
+    fn set_frequency_bins(&mut self, key: K, value: V) {
         if let Some(value_counter) = self.values.get_mut(&key) {
+            let new_weight = value.weight();
+            self.total_weight = self.total_weight - value_counter.weight + new_weight;
             value_counter.value = value;
-            self.update_frequency_bin(&key);
+            value_counter.weight = new_weight;
+            // touch_on_access already increments the admission sketch, so don't double-count here.
+            self.touch_on_access(&key);
+            while !self.values.is_empty() && self.total_weight > self.capacity as u64 {
+                let len_before = self.values.len();
+                self.evict();
+                if self.values.len() == len_before {
+                    // Every resident entry is pinned by the policy, so eviction can't make room: admit
+                    // the heavier value over budget rather than spinning forever.
+                    break;
+                }
+            }
             return;
         }
-        if self.len() >= self.capacity {
+
+        if let Some(sketch) = match &mut self.eviction {
+            Eviction::FrequencyBins { admission, .. } => admission.as_mut(),
+            Eviction::S3Fifo(_) => None,
+        } {
+            sketch.increment(&key);
+        }
+
+        let weight = value.weight();
+        let at_capacity = !self.values.is_empty() && self.total_weight + weight > self.capacity as u64;
+        let has_admission = matches!(&self.eviction, Eviction::FrequencyBins { admission: Some(_), .. });
+        if at_capacity && has_admission {
+            if let Some(victim_key) = self.peek_lfu_key() {
+                let admission = match &self.eviction {
+                    Eviction::FrequencyBins { admission, .. } => admission.as_ref().unwrap(),
+                    Eviction::S3Fifo(_) => unreachable!(),
+                };
+                if admission.estimate(&key) < admission.estimate(&victim_key) {
+                    // The admission filter estimates this key is colder than the entry it would have to evict.
+                    return;
+                }
+            }
+        }
+
+        while !self.values.is_empty() && self.total_weight + weight > self.capacity as u64 {
+            let len_before = self.values.len();
             self.evict();
+            if self.values.len() == len_before {
+                // Every resident entry is pinned by the policy, so eviction can't make room: admit
+                // the new entry over budget rather than spinning forever.
+                break;
+            }
+        }
+
+        self.total_weight += weight;
+        self.values.insert(
+            key.clone(),
+            ValueCounter {
+                value,
+                count: 1,
+                weight,
+                s3fifo_counter: 0,
+            },
+        );
+        if let Eviction::FrequencyBins {
+            frequency_bin,
+            min_frequency,
+            ..
+        } = &mut self.eviction
+        {
+            *min_frequency = 1;
+            frequency_bin.entry(1).or_default().insert(key);
+        }
+    }
+
+    fn set_s3fifo(&mut self, key: K, value: V) {
+        if let Some(value_counter) = self.values.get_mut(&key) {
+            let new_weight = value.weight();
+            self.total_weight = self.total_weight - value_counter.weight + new_weight;
+            value_counter.value = value;
+            value_counter.weight = new_weight;
+            return;
+        }
+
+        while self.values.len() >= self.capacity {
+            self.evict_s3fifo();
+        }
+
+        let weight = value.weight();
+        let in_ghost = match &mut self.eviction {
+            Eviction::S3Fifo(q) => q.ghost.remove(&key),
+            Eviction::FrequencyBins { .. } => false,
+        };
+
+        self.total_weight += weight;
+        self.values.insert(
+            key.clone(),
+            ValueCounter {
+                value,
+                count: 1,
+                weight,
+                s3fifo_counter: 0,
+            },
+        );
+
+        if let Eviction::S3Fifo(q) = &mut self.eviction {
+            if in_ghost {
+                q.main.insert(key);
+            } else {
+                q.small.insert(key);
+            }
         }
-        self.values
-            .insert(key.clone(), ValueCounter { value, count: 1 });
-        self.min_frequency = 1;
-        self.frequency_bin
-            .entry(self.min_frequency)
-            .or_default()
-            .insert(key);
     }
 }
 
@@ -167,7 +685,7 @@ impl<K, V> Iterator for LfuConsumer<K, V> {
     }
 }
 
-impl<K: Eq + Hash + Clone, V> IntoIterator for LFUCache<K, V> {
+impl<K: Eq + Hash + Clone, V: Entry, P: Policy<K, V>, S: BuildHasher> IntoIterator for LFUCache<K, V, P, S> {
     type Item = (K, V);
     type IntoIter = LfuConsumer<K, V>;
 
@@ -186,7 +704,9 @@ impl<'a, K: Hash + Eq + Clone, V> Iterator for LfuIterator<'a, K, V> {
     }
 }
 
-impl<'a, K: Hash + Eq + Clone, V> IntoIterator for &'a LFUCache<K, V> {
+impl<'a, K: Hash + Eq + Clone, V: Entry, P: Policy<K, V>, S: BuildHasher + Default> IntoIterator
+    for &'a LFUCache<K, V, P, S>
+{
     type Item = (&'a K, &'a V);
 
     type IntoIter = LfuIterator<'a, K, V>;
@@ -196,7 +716,7 @@ impl<'a, K: Hash + Eq + Clone, V> IntoIterator for &'a LFUCache<K, V> {
     }
 }
 
-impl<K: Hash + Eq + Clone, V> Index<K> for LFUCache<K, V> {
+impl<K: Hash + Eq + Clone, V: Entry, P: Policy<K, V>, S: BuildHasher> Index<K> for LFUCache<K, V, P, S> {
     type Output = V;
     fn index(&self, index: K) -> &Self::Output {
         return self.values.get(&Rc::new(index)).map(|x| &x.value).unwrap();
@@ -324,4 +844,231 @@ mod tests {
         lfu.set(3, 3);
         assert_eq!(lfu.get(&2), None);
     }
+
+    #[derive(Debug, PartialEq)]
+    struct Blob(u64);
+
+    impl Entry for Blob {
+        fn weight(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_weighted_eviction() {
+        let mut lfu = LFUCache::with_capacity(10);
+        lfu.set(1, Blob(6));
+        lfu.set(2, Blob(4));
+        assert_eq!(lfu.total_weight(), 10);
+        // No room for a weight-5 entry without evicting the least-frequently-used one (key 1).
+        lfu.set(3, Blob(5));
+        assert!(lfu.get(&1).is_none());
+        assert!(lfu.get(&2).is_some());
+        assert!(lfu.get(&3).is_some());
+        assert_eq!(lfu.total_weight(), 9);
+    }
+
+    #[test]
+    fn test_weighted_update_triggers_eviction() {
+        let mut lfu = LFUCache::with_capacity(10);
+        lfu.set(1, Blob(3));
+        lfu.set(2, Blob(3));
+        lfu.set(3, Blob(3));
+        assert_eq!(lfu.total_weight(), 9);
+
+        // Re-setting an existing key to a heavier value must still respect capacity, evicting
+        // other entries (key 1 is now the most-frequently-used, so 2 and 3 go first) as needed.
+        lfu.set(1, Blob(8));
+        assert!(lfu.total_weight() <= 10);
+        assert_eq!(lfu.get(&1), Some(&Blob(8)));
+    }
+
+    struct PinEvens;
+
+    impl Policy<i32, i32> for PinEvens {
+        fn can_evict(&self, key: &i32, _value: &i32) -> bool {
+            key % 2 != 0
+        }
+    }
+
+    #[test]
+    fn test_policy_vetoes_eviction() {
+        let mut lfu = LFUCache::with_capacity_and_policy(2, PinEvens);
+        lfu.set(2, 2);
+        lfu.set(1, 1);
+        // Key 2 is the least-frequently-used entry but is pinned, so key 1 is evicted instead.
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&2), Some(&2));
+        assert_eq!(lfu.get(&1), None);
+    }
+
+    struct PinAll;
+
+    impl Policy<i32, i32> for PinAll {
+        fn can_evict(&self, _key: &i32, _value: &i32) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_policy_vetoing_everything_admits_over_capacity_instead_of_hanging() {
+        let mut lfu = LFUCache::with_capacity_and_policy(2, PinAll);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        // No resident entry is evictable, so this has to be admitted over budget rather than loop forever.
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&1), Some(&1));
+        assert_eq!(lfu.get(&2), Some(&2));
+        assert_eq!(lfu.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn test_tinylfu_admits_hot_candidate() {
+        let mut lfu = LFUCache::with_capacity_tinylfu(2);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+
+        // Key 3 has never been cached, but callers keep asking for it: a scan-resistant hot key.
+        for _ in 0..5 {
+            lfu.get(&3);
+        }
+
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&3), Some(&3));
+        assert_eq!(lfu.get(&1), None);
+        assert_eq!(lfu.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn test_tinylfu_rejects_cold_candidate() {
+        let mut lfu = LFUCache::with_capacity_tinylfu(2);
+
+        // Key 1 is requested heavily before it's ever cached, so its estimate stays high after insertion.
+        for _ in 0..5 {
+            lfu.get(&1);
+        }
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+
+        // Key 3 is a one-off request: its estimate is lower than the eviction victim's (key 1), so it's dropped.
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&3), None);
+        assert_eq!(lfu.get(&1), Some(&1));
+        assert_eq!(lfu.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn test_tinylfu_set_increments_sketch_once_per_call() {
+        let mut lfu = LFUCache::with_capacity_tinylfu(4);
+        lfu.set(1, 1); // new key: one increment
+        lfu.set(1, 2); // update of an existing key: one more increment, not two
+
+        let estimate = match &lfu.eviction {
+            Eviction::FrequencyBins { admission, .. } => admission.as_ref().unwrap().estimate(&1),
+            Eviction::S3Fifo(_) => unreachable!(),
+        };
+        assert_eq!(estimate, 2);
+    }
+
+    #[test]
+    fn test_s3fifo_evicts_oldest_unread_key() {
+        let mut lfu = LFUCache::with_capacity_s3fifo(2);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&1), None);
+        assert_eq!(lfu.get(&2), Some(&2));
+        assert_eq!(lfu.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn test_s3fifo_promotes_reaccessed_key() {
+        let mut lfu = LFUCache::with_capacity_s3fifo(3);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+
+        // Key 1 gets a second chance because it was read before it was popped from the front of the small queue.
+        let _ = lfu.get(&1);
+
+        lfu.set(4, 4);
+        assert_eq!(lfu.get(&1), Some(&1));
+        assert_eq!(lfu.get(&2), None);
+        assert_eq!(lfu.get(&3), Some(&3));
+        assert_eq!(lfu.get(&4), Some(&4));
+    }
+
+    #[test]
+    fn test_set_capacity_shrinks_and_evicts() {
+        let mut lfu = LFUCache::with_capacity(3);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+        assert_eq!(lfu.capacity(), 3);
+
+        lfu.set_capacity(1);
+        assert_eq!(lfu.capacity(), 1);
+        assert_eq!(lfu.len(), 1);
+        assert_eq!(lfu.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn test_set_capacity_shrink_with_policy_vetoing_everything_does_not_hang() {
+        let mut lfu = LFUCache::with_capacity_and_policy(3, PinAll);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+
+        // No resident entry is evictable, so the cache stays over the new (smaller) capacity
+        // instead of looping forever trying to shrink.
+        lfu.set_capacity(1);
+        assert_eq!(lfu.capacity(), 1);
+        assert_eq!(lfu.len(), 3);
+    }
+
+    #[test]
+    fn test_set_capacity_s3fifo_shrinks_and_evicts() {
+        let mut lfu = LFUCache::with_capacity_s3fifo(4);
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+
+        lfu.set_capacity(2);
+        assert_eq!(lfu.capacity(), 2);
+        assert!(lfu.len() <= 2);
+    }
+
+    #[test]
+    fn test_with_capacity_and_hasher() {
+        let mut lfu =
+            LFUCache::with_capacity_and_hasher(2, std::collections::hash_map::RandomState::new());
+        lfu.set(1, 1);
+        lfu.set(2, 2);
+        lfu.set(3, 3);
+        assert_eq!(lfu.get(&1), None);
+        assert_eq!(lfu.get(&2), Some(&2));
+        assert_eq!(lfu.get(&3), Some(&3));
+    }
+
+    #[test]
+    fn test_stats_track_hits_and_misses() {
+        let mut lfu = LFUCache::with_capacity(2);
+        lfu.set(1, 1);
+
+        lfu.get(&1); // hit
+        lfu.get(&2); // miss
+        lfu.get(&1); // hit
+
+        let stats = lfu.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.len, 1);
+        assert_eq!(stats.capacity, 2);
+        assert!((stats.hit_ratio() - 2.0 / 3.0).abs() < f64::EPSILON);
+
+        lfu.reset_stats();
+        let stats = lfu.stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.misses, 0);
+    }
 }
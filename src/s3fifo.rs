@@ -0,0 +1,34 @@
+//! The queues behind [S3-FIFO](https://blog.jasony.me/system/cache/2023/08/01/s3fifo), used by
+//! [`LFUCache::with_capacity_s3fifo`](crate::LFUCache::with_capacity_s3fifo) as a cheaper alternative to the
+//! frequency-bin bookkeeping the default eviction mode uses.
+
+use linked_hash_set::LinkedHashSet;
+use std::hash::{BuildHasher, Hash};
+
+/// Small ("S"), main ("M") and ghost ("G") FIFO queues. `small` and `main` together hold every live key exactly
+/// once; `ghost` remembers recently-evicted keys (with no values) so they can be promoted straight into `main` if
+/// they're requested again soon.
+#[derive(Clone, Debug)]
+pub(crate) struct S3Fifo<K: Hash + Eq + Clone, S: BuildHasher> {
+    pub(crate) small: LinkedHashSet<K, S>,
+    pub(crate) main: LinkedHashSet<K, S>,
+    pub(crate) ghost: LinkedHashSet<K, S>,
+    pub(crate) small_capacity: usize,
+    pub(crate) main_capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, S: BuildHasher + Clone> S3Fifo<K, S> {
+    /// `small_capacity` is ~10% of `capacity` (at least 1); `main_capacity` is the rest, so the two queues' quotas
+    /// always sum to exactly `capacity`. `hash_builder` is cloned into each of the three queues.
+    pub(crate) fn new(capacity: usize, hash_builder: S) -> S3Fifo<K, S> {
+        let small_capacity = (capacity / 10).max(1).min(capacity);
+        let main_capacity = capacity - small_capacity;
+        S3Fifo {
+            small: LinkedHashSet::with_hasher(hash_builder.clone()),
+            main: LinkedHashSet::with_hasher(hash_builder.clone()),
+            ghost: LinkedHashSet::with_hasher(hash_builder),
+            small_capacity,
+            main_capacity,
+        }
+    }
+}
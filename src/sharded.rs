@@ -0,0 +1,103 @@
+//! A thread-safe [`LFUCache`] wrapper that partitions keys across independent shards, used by
+//! [`ShardedLFUCache`] to spread concurrent access across several smaller locks instead of one global one.
+
+use crate::{Entry, LFUCache, NoopPolicy};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Wraps `shard_count` independent [`LFUCache`]s behind their own [`Mutex`], picking a shard per key by hash so the
+/// cache can be shared across threads (e.g. via [`Arc`](std::sync::Arc)) without contending on a single global
+/// lock. Each shard is sized to roughly `total_capacity / shard_count`.
+///
+/// Unlike [`LFUCache::get`], which borrows `&mut self` to bump the accessed key's frequency, every method here
+/// takes `&self`: the mutation happens through the shard's `Mutex` instead. Values must be [`Clone`] since a
+/// reference into a shard can't outlive that shard's lock.
+pub struct ShardedLFUCache<K: Hash + Eq + Clone, V: Entry + Clone> {
+    shards: Vec<Mutex<LFUCache<K, V, NoopPolicy>>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Entry + Clone> ShardedLFUCache<K, V> {
+    /// Creates a sharded cache with `shard_count` shards, each with a capacity of `total_capacity / shard_count`
+    /// (at least `1`).
+    pub fn new(total_capacity: usize, shard_count: usize) -> ShardedLFUCache<K, V> {
+        if shard_count == 0 {
+            panic!("Unable to create cache: shard_count is {:?}", shard_count);
+        }
+        let shard_capacity = (total_capacity / shard_count).max(1);
+        let shards = (0..shard_count)
+            .map(|_| Mutex::new(LFUCache::with_capacity(shard_capacity)))
+            .collect();
+        ShardedLFUCache { shards }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<LFUCache<K, V, NoopPolicy>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index = hasher.finish() as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    /// Returns a clone of the value associated with `key` (if it still exists), bumping its frequency in its shard.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.shard(key).lock().unwrap().get(key).cloned()
+    }
+
+    pub fn set(&self, key: K, value: V) {
+        self.shard(&key).lock().unwrap().set(key, value);
+    }
+
+    pub fn remove(&self, key: K) -> Option<V> {
+        self.shard(&key).lock().unwrap().remove(key)
+    }
+
+    /// The total number of entries across all shards.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_set_remove_round_trip() {
+        let cache: ShardedLFUCache<i32, i32> = ShardedLFUCache::new(8, 4);
+        cache.set(1, 10);
+        cache.set(2, 20);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&2), Some(20));
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.remove(1), Some(10));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let cache = Arc::new(ShardedLFUCache::<i32, i32>::new(64, 8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    cache.set(i, i * 10);
+                    assert_eq!(cache.get(&i), Some(i * 10));
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}